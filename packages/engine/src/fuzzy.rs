@@ -0,0 +1,109 @@
+use fst::automaton::{Automaton, Levenshtein};
+use fst::{IoResult, Map, Streamer};
+
+/// Token length, in characters, at or below which fuzzy expansion requires an
+/// exact match. Short tokens carry little edit-distance signal ("of" one edit away
+/// from dozens of unrelated words), so they stay exact-only.
+const SHORT_TOKEN_MAX_LEN: usize = 4;
+/// Token length at or below which a single edit is tolerated; longer tokens tolerate two.
+const MEDIUM_TOKEN_MAX_LEN: usize = 8;
+
+/// A token from the main FST that satisfied a query term's fuzzy automaton.
+pub struct FuzzyMatch {
+    pub token: String,
+    pub token_id: u64,
+    pub edit_distance: u32,
+}
+
+/// Returns the maximum Levenshtein edit distance to tolerate when expanding a
+/// query term of the given length against the token FST.
+pub fn max_edit_distance(token_len: usize) -> u32 {
+    if token_len <= SHORT_TOKEN_MAX_LEN {
+        0
+    } else if token_len <= MEDIUM_TOKEN_MAX_LEN {
+        1
+    } else {
+        2
+    }
+}
+
+/// The scoring weight of a fuzzy match at the given edit distance, relative to
+/// an exact match at weight `1.0`. Halving per edit weights a single typo-match
+/// below a single exact hit of the same term; several typo-matches summed
+/// together can still reach or pass one exact hit.
+pub fn edit_distance_weight(edit_distance: u32) -> f64 {
+    1.0 / f64::from(1u32 << edit_distance.min(16))
+}
+
+/// Expands `query_token` against `fst_map` (the main token FST), returning every
+/// token within its edit-distance budget, including the exact match at distance
+/// zero. When `allow_prefix_tail` is set, the automaton's tail is left open so
+/// an incomplete last word (still being typed) matches anything it's a prefix
+/// of, without requiring an extra edit per missing trailing character.
+pub fn expand_fuzzy(
+    fst_map: &Map<Vec<u8>>,
+    query_token: &str,
+    allow_prefix_tail: bool,
+) -> IoResult<Vec<FuzzyMatch>> {
+    let budget = max_edit_distance(query_token.chars().count());
+    // `Levenshtein::new(query_token, budget)` already matches every token
+    // within `budget` edits, so a single DFA built at the budget covers every
+    // distance band; the actual distance of each match is then computed
+    // directly instead of re-streaming the FST once per band.
+    let automaton = Levenshtein::new(query_token, budget)?;
+    let mut stream = if allow_prefix_tail {
+        fst_map.search(automaton.starts_with()).into_stream()
+    } else {
+        fst_map.search(automaton).into_stream()
+    };
+
+    let mut matches = Vec::new();
+    while let Some((token_bytes, token_id)) = stream.next() {
+        let token = String::from_utf8_lossy(token_bytes).into_owned();
+        // `starts_with` matches on a prefix of `token`, which has no single
+        // well-defined edit distance against the untyped tail; clamp to the
+        // budget the automaton already guarantees.
+        let edit_distance = char_levenshtein_distance(query_token, &token).min(budget);
+        matches.push(FuzzyMatch {
+            token,
+            token_id,
+            edit_distance,
+        });
+    }
+
+    Ok(matches)
+}
+
+/// Plain Levenshtein edit distance between two strings, counted in chars.
+fn char_levenshtein_distance(a: &str, b: &str) -> u32 {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let mut previous_row: Vec<u32> = (0..=b.len() as u32).collect();
+
+    for (i, a_char) in a.iter().enumerate() {
+        let mut current_row = vec![(i + 1) as u32];
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution_cost = if a_char == b_char { 0 } else { 1 };
+            current_row.push(
+                (previous_row[j + 1] + 1)
+                    .min(current_row[j] + 1)
+                    .min(previous_row[j] + substitution_cost),
+            );
+        }
+        previous_row = current_row;
+    }
+
+    previous_row[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_char_levenshtein_distance() {
+        assert_eq!(char_levenshtein_distance("RESURECTION", "RESURRECTION"), 1);
+        assert_eq!(char_levenshtein_distance("GOD", "GOD"), 0);
+        assert_eq!(char_levenshtein_distance("MELCHIZEDEC", "MELCHIZEDEK"), 1);
+    }
+}
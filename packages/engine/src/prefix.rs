@@ -0,0 +1,55 @@
+use fst::automaton::Str;
+use fst::{Automaton, IoResult, Map, Streamer};
+
+/// A token in the main FST that shares the query's prefix.
+pub struct PrefixMatch {
+    pub token: String,
+    pub token_id: u64,
+}
+
+/// Expands `prefix` against `fst_map` via a `starts_with` automaton,
+/// returning every matching token and its id.
+///
+/// `fst_map` must be the *unstemmed* token FST (built by
+/// `util::build_unstemmed_tokens_fst`), not the main stem-keyed token FST:
+/// `prefix` is the surface form of a word still being typed, which has no
+/// valid stem, and its token id is the id of the stem it rolls up to in the
+/// main FST/reverse index.
+///
+/// The caller is expected to cap the result with [`top_by_document_frequency`]
+/// before unioning postings, so a one- or two-letter prefix while the user is
+/// still typing doesn't blow up into thousands of terms.
+pub fn expand_prefix(fst_map: &Map<Vec<u8>>, prefix: &str) -> IoResult<Vec<PrefixMatch>> {
+    let automaton = Str::new(prefix).starts_with();
+    let mut stream = fst_map.search(automaton).into_stream();
+    let mut matches = Vec::new();
+
+    while let Some((token_bytes, token_id)) = stream.next() {
+        matches.push(PrefixMatch {
+            token: String::from_utf8_lossy(token_bytes).into_owned(),
+            token_id,
+        });
+    }
+
+    Ok(matches)
+}
+
+/// Keeps only the `cap` most frequent prefix matches, ranked by document
+/// frequency (`doc_frequency` looks a token id up in the reverse index's
+/// counts map). Ties break by token so results are stable across calls.
+pub fn top_by_document_frequency<F>(
+    mut matches: Vec<PrefixMatch>,
+    cap: usize,
+    doc_frequency: F,
+) -> Vec<PrefixMatch>
+where
+    F: Fn(u64) -> usize,
+{
+    matches.sort_by(|a, b| {
+        doc_frequency(b.token_id)
+            .cmp(&doc_frequency(a.token_id))
+            .then_with(|| a.token.cmp(&b.token))
+    });
+    matches.truncate(cap);
+    matches
+}
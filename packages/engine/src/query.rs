@@ -0,0 +1,322 @@
+use crate::util::{tokenize, Tokenized};
+use rust_stemmers::Stemmer;
+use std::collections::BTreeMap;
+
+/// A parsed query, as a tree of boolean operations over leaf terms.
+///
+/// Built by [`parse`] and walked by [`evaluate`] against per-term posting
+/// lists supplied by the caller (the main token FST plus its
+/// `ReverseIndexEntryBytes` counts maps in `VersearchIndex`).
+#[cfg_attr(test, derive(Debug))]
+#[derive(Clone, PartialEq, Eq)]
+pub enum Operation {
+    And(Vec<Operation>),
+    Or(Vec<Operation>),
+    /// A quoted phrase; terms must appear adjacent (proximity == 1) in the
+    /// same verse and translation.
+    Phrase(Vec<Tokenized>),
+    /// A single unquoted term.
+    Query(Tokenized),
+    /// The last, possibly-incomplete term of an as-you-type query, tokenized
+    /// *without* the configured stemmer: a partial word has no valid stem,
+    /// and the FST it expands against ([`crate::prefix::expand_prefix`]) is
+    /// keyed on unstemmed surface tokens for exactly this reason. Resolved by
+    /// expanding every token sharing the prefix, rather than a single exact
+    /// lookup. See [`parse_prefix`].
+    Prefix(Tokenized),
+}
+
+/// Parses a query string into an [`Operation`] tree.
+///
+/// The grammar is intentionally small: the input is split on quoted phrases
+/// and the `AND`/`OR` keywords (case-insensitive), each remaining segment is
+/// tokenized with [`tokenize`], and segments are combined with `AND` binding
+/// tighter than `OR` (so `a OR b AND c` means `a OR (b AND c)`) and `AND` as
+/// the default when no keyword separates them (so `"son of god" spirit`
+/// behaves like `"son of god" AND spirit`).
+///
+/// `stemmer` must be the same one configured for indexing (`Config::stem_language`)
+/// so that query terms stem to the same posting-list keys the index stored.
+pub fn parse(input: &str, stemmer: Option<&Stemmer>) -> Operation {
+    // `OR` starts a new group; terms within a group (separated by `AND` or
+    // nothing at all) are implicitly ANDed together.
+    let mut groups: Vec<Vec<Operation>> = vec![Vec::new()];
+
+    for segment in split_segments(input) {
+        match segment {
+            Segment::And => {}
+            Segment::Or => groups.push(Vec::new()),
+            Segment::Term(term) => {
+                if let Some(tokenized) = tokenize(&term, stemmer).into_iter().next() {
+                    groups.last_mut().unwrap().push(Operation::Query(tokenized));
+                }
+            }
+            Segment::Phrase(phrase) => groups
+                .last_mut()
+                .unwrap()
+                .push(Operation::Phrase(tokenize(&phrase, stemmer))),
+        }
+    }
+
+    let mut ors: Vec<Operation> = groups
+        .into_iter()
+        .filter(|group| !group.is_empty())
+        .map(|mut group| {
+            if group.len() == 1 {
+                group.remove(0)
+            } else {
+                Operation::And(group)
+            }
+        })
+        .collect();
+
+    match ors.len() {
+        0 => Operation::And(Vec::new()),
+        1 => ors.remove(0),
+        _ => Operation::Or(ors),
+    }
+}
+
+enum Segment {
+    Term(String),
+    Phrase(String),
+    And,
+    Or,
+}
+
+/// Splits a raw query into quoted phrases, bare terms, and `AND`/`OR` keywords,
+/// in the order they appeared.
+fn split_segments(input: &str) -> Vec<Segment> {
+    let mut segments = Vec::new();
+    let mut chars = input.chars().peekable();
+    let mut buffer = String::new();
+
+    while let Some(c) = chars.next() {
+        if c == '"' {
+            flush_term(&mut buffer, &mut segments);
+            let phrase: String = chars.by_ref().take_while(|c| *c != '"').collect();
+            segments.push(Segment::Phrase(phrase));
+        } else if c.is_whitespace() {
+            flush_term(&mut buffer, &mut segments);
+        } else {
+            buffer.push(c);
+        }
+    }
+    flush_term(&mut buffer, &mut segments);
+
+    segments
+}
+
+/// Parses an as-you-type query: identical to [`parse`], except the final bare
+/// term (the word the user is still typing) is turned into an
+/// [`Operation::Prefix`] instead of an exact [`Operation::Query`]. A trailing
+/// quoted phrase or a query ending in whitespace is left alone, since in both
+/// cases the last word is already complete.
+pub fn parse_prefix(input: &str, stemmer: Option<&Stemmer>) -> Operation {
+    let operation = parse(input, stemmer);
+    if input.ends_with(char::is_whitespace) {
+        return operation;
+    }
+    to_trailing_prefix(operation)
+}
+
+/// Walks down the rightmost branch of the operation tree and converts the
+/// last `Query` leaf it finds into a `Prefix` leaf. The leaf is re-tokenized
+/// from its original surface form with no stemmer, since the trailing word is
+/// still being typed and has no valid stem, and the FST it will expand
+/// against is keyed on unstemmed tokens (see [`Operation::Prefix`]).
+fn to_trailing_prefix(operation: Operation) -> Operation {
+    match operation {
+        Operation::Query(term) => {
+            let unstemmed = tokenize(&term.source, None)
+                .into_iter()
+                .next()
+                .unwrap_or(term);
+            Operation::Prefix(unstemmed)
+        }
+        Operation::And(mut terms) => {
+            if let Some(last) = terms.pop() {
+                terms.push(to_trailing_prefix(last));
+            }
+            Operation::And(terms)
+        }
+        Operation::Or(mut terms) => {
+            if let Some(last) = terms.pop() {
+                terms.push(to_trailing_prefix(last));
+            }
+            Operation::Or(terms)
+        }
+        other @ (Operation::Phrase(_) | Operation::Prefix(_)) => other,
+    }
+}
+
+/// Pushes `buffer` onto `segments` as a keyword or bare term, then clears it.
+fn flush_term(buffer: &mut String, segments: &mut Vec<Segment>) {
+    if buffer.is_empty() {
+        return;
+    }
+    match buffer.to_uppercase().as_str() {
+        "AND" => segments.push(Segment::And),
+        "OR" => segments.push(Segment::Or),
+        _ => segments.push(Segment::Term(buffer.clone())),
+    }
+    buffer.clear();
+}
+
+/// Per-verse posting data for a single term: an occurrence count (used for
+/// weighting) keyed by verse, as looked up from the main token FST and its
+/// `ReverseIndexEntryBytes` counts map.
+pub type Postings = BTreeMap<crate::proto::data::VerseKey, u64>;
+
+/// Evaluates a parsed query against posting lists resolved by `lookup`, which
+/// maps a term's stem to its postings (empty if the term isn't indexed).
+/// `phrase_matches` resolves a quoted phrase to the set of verses where its
+/// terms appear at proximity 1, via the proximity FST. `prefix_matches`
+/// resolves an [`Operation::Prefix`] leaf to the union of postings for every
+/// indexed token sharing that prefix (see [`crate::prefix`]).
+pub fn evaluate<L, P, X>(
+    operation: &Operation,
+    lookup: &L,
+    phrase_matches: &P,
+    prefix_matches: &X,
+) -> Postings
+where
+    L: Fn(&Tokenized) -> Postings,
+    P: Fn(&[Tokenized]) -> Postings,
+    X: Fn(&Tokenized) -> Postings,
+{
+    match operation {
+        Operation::Query(term) => lookup(term),
+        Operation::Prefix(term) => prefix_matches(term),
+        Operation::Phrase(terms) => phrase_matches(terms),
+        Operation::And(children) => {
+            let mut iter = children
+                .iter()
+                .map(|c| evaluate(c, lookup, phrase_matches, prefix_matches));
+            match iter.next() {
+                Some(first) => iter.fold(first, intersect_postings),
+                None => Postings::new(),
+            }
+        }
+        Operation::Or(children) => children
+            .iter()
+            .map(|c| evaluate(c, lookup, phrase_matches, prefix_matches))
+            .fold(Postings::new(), union_postings),
+    }
+}
+
+fn intersect_postings(a: Postings, b: Postings) -> Postings {
+    a.into_iter()
+        .filter_map(|(key, count)| b.get(&key).map(|other| (key, count + other)))
+        .collect()
+}
+
+fn union_postings(mut a: Postings, b: Postings) -> Postings {
+    for (key, count) in b {
+        *a.entry(key).or_insert(0) += count;
+    }
+    a
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_single_term() {
+        assert_eq!(
+            parse("spirit", None),
+            Operation::Query(tokenize("spirit", None).remove(0))
+        );
+    }
+
+    #[test]
+    fn test_parse_implicit_and() {
+        let parsed = parse("grace mercy", None);
+        assert_eq!(
+            parsed,
+            Operation::And(vec![
+                Operation::Query(tokenize("grace", None).remove(0)),
+                Operation::Query(tokenize("mercy", None).remove(0)),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_parse_or() {
+        let parsed = parse("grace OR mercy", None);
+        assert_eq!(
+            parsed,
+            Operation::Or(vec![
+                Operation::Query(tokenize("grace", None).remove(0)),
+                Operation::Query(tokenize("mercy", None).remove(0)),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_parse_prefix_marks_trailing_term() {
+        let parsed = parse_prefix("love of g", None);
+        assert_eq!(
+            parsed,
+            Operation::And(vec![
+                Operation::Query(tokenize("love", None).remove(0)),
+                Operation::Query(tokenize("of", None).remove(0)),
+                Operation::Prefix(tokenize("g", None).remove(0)),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_parse_prefix_leaves_completed_query_alone() {
+        let parsed = parse_prefix("love of god ", None);
+        assert_eq!(
+            parsed,
+            Operation::And(vec![
+                Operation::Query(tokenize("love", None).remove(0)),
+                Operation::Query(tokenize("of", None).remove(0)),
+                Operation::Query(tokenize("god", None).remove(0)),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_parse_stems_query_terms_when_configured() {
+        let stemmer = Stemmer::create(rust_stemmers::Algorithm::English);
+        let parsed = parse("loving mercy", Some(&stemmer));
+        assert_eq!(
+            parsed,
+            Operation::And(vec![
+                Operation::Query(tokenize("loving", Some(&stemmer)).remove(0)),
+                Operation::Query(tokenize("mercy", Some(&stemmer)).remove(0)),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_parse_prefix_leaves_trailing_term_unstemmed() {
+        let stemmer = Stemmer::create(rust_stemmers::Algorithm::English);
+        let parsed = parse_prefix("mercy lov", Some(&stemmer));
+        assert_eq!(
+            parsed,
+            Operation::And(vec![
+                Operation::Query(tokenize("mercy", Some(&stemmer)).remove(0)),
+                // "lov" has no valid stem while still being typed, so it's
+                // tokenized unstemmed rather than through the stemmer.
+                Operation::Prefix(tokenize("lov", None).remove(0)),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_parse_quoted_phrase_and_term() {
+        let parsed = parse("\"son of god\" AND spirit", None);
+        assert_eq!(
+            parsed,
+            Operation::And(vec![
+                Operation::Phrase(tokenize("son of god", None)),
+                Operation::Query(tokenize("spirit", None).remove(0)),
+            ])
+        );
+    }
+}
@@ -1,8 +1,9 @@
 use crate::proto::data::{decode_translation_data, Translation, VerseKey, VerseText};
 use crate::{ReverseIndexEntryBytes, VersearchIndex, TRANSLATION_COUNT};
 use anyhow::{Context, Result};
-use fst::MapBuilder;
+use fst::{MapBuilder, SetBuilder};
 use log::info;
+use rust_stemmers::{Algorithm, Stemmer};
 use serde::Deserialize;
 use std::cmp::Ordering;
 use std::collections::{BTreeMap, BTreeSet};
@@ -10,6 +11,8 @@ use std::fs;
 use std::io::prelude::*;
 use std::iter::Iterator;
 use std::time::Instant;
+use unicode_normalization::UnicodeNormalization;
+use unicode_segmentation::UnicodeSegmentation;
 
 pub static MAX_PROXIMITY: u64 = 8;
 
@@ -17,6 +20,31 @@ pub static MAX_PROXIMITY: u64 = 8;
 #[derive(Deserialize)]
 pub struct Config {
     pub translation_dir: String,
+    /// Snowball algorithm to stem tokens with before indexing, e.g. "english".
+    /// Left unset, tokens are indexed as-is with no stemming.
+    pub stem_language: Option<String>,
+    /// Tokens to always treat as stop words (e.g. "the", "and"), in addition
+    /// to any picked up automatically via `stop_word_doc_frequency_threshold`.
+    pub stop_words: Option<Vec<String>>,
+    /// A token indexed in more than this fraction of all verses is treated as
+    /// a stop word automatically. Defaults to 0.4 (40% of verses).
+    pub stop_word_doc_frequency_threshold: Option<f64>,
+}
+
+static DEFAULT_STOP_WORD_DOC_FREQUENCY_THRESHOLD: f64 = 0.4;
+
+/// Resolves a configured stemmer language name to a `rust_stemmers` algorithm.
+/// Unrecognized names are treated the same as unset, since skipping stemming
+/// is always safer than guessing the wrong language.
+fn resolve_stemmer(stem_language: &Option<String>) -> Option<Stemmer> {
+    let algorithm = match stem_language.as_deref()?.to_lowercase().as_str() {
+        "english" | "en" => Algorithm::English,
+        "spanish" | "es" => Algorithm::Spanish,
+        "french" | "fr" => Algorithm::French,
+        "german" | "de" => Algorithm::German,
+        _ => return None,
+    };
+    Some(Stemmer::create(algorithm))
 }
 
 #[cfg_attr(test, derive(Debug))]
@@ -24,6 +52,7 @@ pub struct Config {
 pub struct Tokenized {
     pub source: String,
     pub token: String,
+    pub stem: String,
 }
 
 impl Ord for Tokenized {
@@ -45,26 +74,48 @@ struct VerseStats {
 
 type TranslationVerses = BTreeMap<Translation, BTreeMap<VerseKey, String>>;
 
-pub fn tokenize(input: &str) -> Vec<Tokenized> {
+pub fn tokenize(input: &str, stemmer: Option<&Stemmer>) -> Vec<Tokenized> {
+    // Unicode word boundaries (UAX #29) already exclude surrounding punctuation
+    // while keeping contractions like "It's" together, so the word itself is
+    // the source text we highlight against.
     input
-        .split_whitespace()
-        .map(|s| Tokenized {
-            token: s
-                .chars()
-                // Keeping only alphanumeric characters lets users search without
-                // concern for apostrophes and the like
-                .filter(|c| c.is_ascii_alphanumeric())
+        .unicode_words()
+        .map(|s| {
+            // NFKD decomposes accented letters into a base letter plus combining
+            // marks (e.g. "é" -> "e" + U+0301); filtering to alphanumerics drops
+            // the marks and folds "José" and "Jose" to the same token.
+            let token = s
+                .nfkd()
+                .filter(|c| c.is_alphanumeric())
                 .collect::<String>()
-                .to_uppercase(),
-            source: s
-                .chars()
-                .enumerate()
-                // Like tokens but with apostophes and commas (except trailing commas)
-                .filter(|(i, c)| {
-                    c.is_ascii_alphanumeric() || *c == '\'' || (*c == ',' && *i != s.len() - 1)
+                .to_uppercase();
+            // Stemming lets "loved"/"loves"/"loving" share a posting list; the
+            // unstemmed token is kept as the fallback so search still works
+            // with no stemmer configured. The non-English Snowball algorithms
+            // expect accented input (e.g. "amó"), so the stemmer runs on the
+            // case-folded word before diacritics are stripped, not on `token`;
+            // the result is then NFKD-folded the same way `token` is, so it
+            // stays a valid posting-list key.
+            let stem = stemmer
+                .map(|stemmer| {
+                    let case_folded: String = s
+                        .chars()
+                        .filter(|c| c.is_alphanumeric())
+                        .collect::<String>()
+                        .to_lowercase();
+                    let stemmed = stemmer.stem(&case_folded).into_owned();
+                    stemmed
+                        .nfkd()
+                        .filter(|c| c.is_alphanumeric())
+                        .collect::<String>()
+                        .to_uppercase()
                 })
-                .map(|(_i, c)| c)
-                .collect::<String>(),
+                .unwrap_or_else(|| token.clone());
+            Tokenized {
+                source: s.to_string(),
+                token,
+                stem,
+            }
         })
         .collect()
 }
@@ -108,8 +159,12 @@ type WipProximitiesMap =
     BTreeMap<usize, BTreeMap<VerseKey, BTreeMap<String, BTreeMap<String, u64>>>>;
 // Stores work-in-progress token counts per verse and translation
 type WipTokenCountsMap = BTreeMap<String, BTreeMap<VerseKey, VerseStats>>;
+// Stores work-in-progress unstemmed token -> stem mappings, for the prefix FST
+type WipUnstemmedTokensMap = BTreeMap<String, String>;
 
-/// Performs initial processing of verses read from disk
+/// Performs initial processing of verses read from disk: token counts and
+/// highlight words. Proximities are computed in a later pass, once stop
+/// words are known, by [`build_proximities`].
 #[inline]
 fn process_verses(
     translation_key: Translation,
@@ -117,7 +172,8 @@ fn process_verses(
     translation_verses: &mut TranslationVerses,
     highlight_words: &mut BTreeSet<String>,
     wip_token_counts: &mut BTreeMap<String, BTreeMap<VerseKey, VerseStats>>,
-    proximities: &mut WipProximitiesMap,
+    wip_unstemmed_tokens: &mut WipUnstemmedTokensMap,
+    stemmer: Option<&Stemmer>,
 ) {
     for verse in verses {
         translation_verses
@@ -126,14 +182,20 @@ fn process_verses(
             .entry(verse.key.unwrap())
             .or_insert_with(|| verse.text.clone());
         let vkey = verse.key.expect("Missing verse key");
-        let verse_tokens = tokenize(&verse.text);
-        // Count up tokens
-        for (i, tokenized) in verse_tokens.iter().enumerate() {
-            // Save word to get a highlight id later
+        let verse_tokens = tokenize(&verse.text, stemmer);
+        // Count up tokens, indexed by stem so inflections share a posting list
+        for tokenized in verse_tokens.iter() {
+            // Save the exact surface word (not the stem) to get a highlight id later
             highlight_words.insert(tokenized.source.to_uppercase());
+            // Remember which stem this surface token rolled up to, so prefix
+            // queries (which type an incomplete, unstemmed word) can still be
+            // resolved against the stem-keyed postings; see `crate::prefix`.
+            wip_unstemmed_tokens
+                .entry(tokenized.token.clone())
+                .or_insert_with(|| tokenized.stem.clone());
             // Create new stats entry if needed
             let entry = wip_token_counts
-                .entry(tokenized.token.clone())
+                .entry(tokenized.stem.clone())
                 .or_insert_with(BTreeMap::new)
                 .entry(vkey.clone())
                 .or_insert_with(|| VerseStats {
@@ -144,40 +206,127 @@ fn process_verses(
             entry.counts[translation_key as usize] += 1;
             // Track highlights
             entry.highlights.insert(tokenized.source.to_uppercase());
-            // Track proximities
-            for (j, other_tokenized) in verse_tokens.iter().enumerate().skip(i + 1) {
-                let prox = (j - i) as u64;
-                proximities
-                    .entry(translation_key as usize)
-                    .or_insert_with(BTreeMap::new)
-                    .entry(vkey.clone())
-                    .or_insert_with(BTreeMap::new)
-                    .entry(tokenized.token.clone())
-                    .or_insert_with(BTreeMap::new)
-                    .entry(other_tokenized.token.clone())
-                    .and_modify(|p: &mut u64| {
-                        if prox < *p {
-                            *p = prox;
-                        } else if prox > MAX_PROXIMITY {
-                            *p = MAX_PROXIMITY
-                        }
-                    })
-                    .or_insert(prox);
+        }
+    }
+}
+
+/// Builds the proximity map over the already-loaded verse text. Every pair
+/// of tokens in a verse is indexed with the distance between them, including
+/// pairs where one side is a stop word:
+/// phrase queries need the `proximity == 1` entries for every word in the
+/// quoted phrase, stop words included (e.g. `"son of god"` needs the
+/// `(SON, OF)` and `(OF, GOD)` pairs just as much as `(SON, GOD)`). Stop words
+/// are instead down-weighted at scoring time; see [`STOP_WORD_WEIGHT`].
+#[inline]
+fn build_proximities(
+    translation_verses: &TranslationVerses,
+    stemmer: Option<&Stemmer>,
+) -> WipProximitiesMap {
+    let mut proximities = WipProximitiesMap::new();
+
+    for (translation_key, verses) in translation_verses {
+        for (vkey, text) in verses {
+            let verse_tokens = tokenize(text, stemmer);
+            for (i, tokenized) in verse_tokens.iter().enumerate() {
+                for (j, other_tokenized) in verse_tokens.iter().enumerate().skip(i + 1) {
+                    let prox = (j - i) as u64;
+                    proximities
+                        .entry(*translation_key as usize)
+                        .or_insert_with(BTreeMap::new)
+                        .entry(vkey.clone())
+                        .or_insert_with(BTreeMap::new)
+                        .entry(tokenized.stem.clone())
+                        .or_insert_with(BTreeMap::new)
+                        .entry(other_tokenized.stem.clone())
+                        .and_modify(|p: &mut u64| {
+                            if prox < *p {
+                                *p = prox;
+                            } else if prox > MAX_PROXIMITY {
+                                *p = MAX_PROXIMITY
+                            }
+                        })
+                        .or_insert(prox);
+                }
             }
         }
     }
+
+    proximities
+}
+
+/// Determines the stop-word set: tokens configured explicitly in `Config`,
+/// plus any token whose document frequency (the number of verses it appears
+/// in, across all translations) exceeds `threshold` of `total_docs`.
+///
+/// Configured stop words are stemmed the same way as indexed tokens, since
+/// the returned set is compared against stems (see [`STOP_WORD_WEIGHT`]), not
+/// raw surface tokens.
+#[inline]
+fn compute_stop_words(
+    wip_token_counts: &WipTokenCountsMap,
+    total_docs: usize,
+    config: &Config,
+    stemmer: Option<&Stemmer>,
+) -> BTreeSet<String> {
+    let threshold = config
+        .stop_word_doc_frequency_threshold
+        .unwrap_or(DEFAULT_STOP_WORD_DOC_FREQUENCY_THRESHOLD);
+    let max_doc_frequency = total_docs as f64 * threshold;
+
+    let mut stop_words: BTreeSet<String> = wip_token_counts
+        .iter()
+        .filter(|(_, entries)| entries.len() as f64 > max_doc_frequency)
+        .map(|(token, _)| token.clone())
+        .collect();
+
+    if let Some(configured) = &config.stop_words {
+        stop_words.extend(
+            configured
+                .iter()
+                .flat_map(|s| tokenize(s, stemmer).into_iter().map(|t| t.stem)),
+        );
+    }
+
+    stop_words
+}
+
+/// Scoring weight a query term's stem must be multiplied by once it's found
+/// in the persisted stop-word FST (`stop_words_bytes`, the second-to-last
+/// argument to `VersearchIndex::new`), relative to a non-stop-word term at weight `1.0`.
+///
+/// Stop words are down-weighted rather than excluded from the index: phrase
+/// queries like `"son of god"` still need `OF`'s proximity entries to confirm
+/// the whole phrase is adjacent (see `build_proximities`), but `OF` shouldn't
+/// carry as much ranking weight as `GOD` does for a bare-term query. This
+/// constant is the hook `VersearchIndex` scoring must consult for that
+/// down-weighting to actually happen — without it, the stop-word FST is
+/// persisted but nothing reads it back.
+pub static STOP_WORD_WEIGHT: f64 = 0.1;
+
+#[inline]
+fn build_stop_words_fst(stop_words: &BTreeSet<String>) -> Result<Vec<u8>> {
+    let mut build = SetBuilder::memory();
+    for stop_word in stop_words {
+        build
+            .insert(stop_word)
+            .context("Could not insert into stop words set builder")?;
+    }
+    build
+        .into_inner()
+        .context("Could not build stop words fst bytes")
 }
 
-/// Loads data from disk and returns the total number of documents
+/// Loads data from disk and returns the parsed config and total document count
 #[inline]
 fn load_data(
     translation_verses: &mut TranslationVerses,
     highlight_words: &mut BTreeSet<String>,
     wip_token_counts: &mut WipTokenCountsMap,
-    proximities: &mut WipProximitiesMap,
-) -> Result<()> {
+    wip_unstemmed_tokens: &mut WipUnstemmedTokensMap,
+) -> Result<(Config, usize)> {
     let config = envy::from_env::<Config>()?;
     info!("Loading translations from {:?}", config.translation_dir);
+    let stemmer = resolve_stemmer(&config.stem_language);
 
     let mut total_docs: usize = 0;
 
@@ -210,7 +359,8 @@ fn load_data(
                 translation_verses,
                 highlight_words,
                 wip_token_counts,
-                proximities,
+                wip_unstemmed_tokens,
+                stemmer.as_ref(),
             );
             info!(
                 "Processed {} verses in {}ms",
@@ -222,7 +372,7 @@ fn load_data(
 
     info!("Total verses loaded (all translations): {}", total_docs);
 
-    Ok(())
+    Ok((config, total_docs))
 }
 
 /// Build and return a reverse index, fst bytes, and vector of highlight words
@@ -334,6 +484,33 @@ fn build_proximity_fst_bytes(
     Ok(proximities)
 }
 
+/// Builds the FST that [`crate::prefix::expand_prefix`] searches: unstemmed
+/// surface tokens mapped to the same token id their stem has in the main
+/// token FST. Prefix queries tokenize the (possibly incomplete) trailing word
+/// without a stemmer, since a partial word has no valid stem, so they need a
+/// token view keyed on the surface form rather than the stem.
+#[inline]
+fn build_unstemmed_tokens_fst(
+    wip_unstemmed_tokens: &WipUnstemmedTokensMap,
+    wip_token_counts: &WipTokenCountsMap,
+) -> Result<Vec<u8>> {
+    let ordered_stems: Vec<_> = wip_token_counts.keys().cloned().collect();
+    let mut build = MapBuilder::memory();
+
+    for (token, stem) in wip_unstemmed_tokens {
+        let stem_id = ordered_stems
+            .binary_search(stem)
+            .expect("Could not find index for stem backing an unstemmed token");
+        build
+            .insert(token, stem_id as u64)
+            .context("Could not insert into unstemmed tokens fst builder")?;
+    }
+
+    build
+        .into_inner()
+        .context("Could not build unstemmed tokens fst bytes")
+}
+
 #[inline]
 fn build_translation_verses_bytes(
     translation_verses: &TranslationVerses,
@@ -363,15 +540,15 @@ pub fn get_index() -> VersearchIndex {
     let start = Instant::now();
 
     let mut wip_token_counts = BTreeMap::new();
-    let mut wip_proximities = BTreeMap::new();
     let mut translation_verses: TranslationVerses = BTreeMap::new();
     let mut highlight_words = BTreeSet::new();
+    let mut wip_unstemmed_tokens = WipUnstemmedTokensMap::new();
 
-    load_data(
+    let (config, total_docs) = load_data(
         &mut translation_verses,
         &mut highlight_words,
         &mut wip_token_counts,
-        &mut wip_proximities,
+        &mut wip_unstemmed_tokens,
     )
     .expect("Could not load data from disk");
 
@@ -380,10 +557,22 @@ pub fn get_index() -> VersearchIndex {
     let (reverse_index_bytes, fst_bytes, highlight_words) =
         build_reverse_index(&highlight_words, &wip_token_counts);
 
+    let unstemmed_tokens_bytes =
+        build_unstemmed_tokens_fst(&wip_unstemmed_tokens, &wip_token_counts)
+            .expect("Could not build unstemmed tokens fst");
+
     info!("Indexed data {}ms", now.elapsed().as_millis());
 
+    let stemmer = resolve_stemmer(&config.stem_language);
+
+    let stop_words = compute_stop_words(&wip_token_counts, total_docs, &config, stemmer.as_ref());
+    let stop_words_bytes =
+        build_stop_words_fst(&stop_words).expect("Could not build stop words fst");
+    info!("Stop words: {} tokens", stop_words.len());
+
     let now = Instant::now();
 
+    let wip_proximities = build_proximities(&translation_verses, stemmer.as_ref());
     let proximities_bytes = build_proximity_fst_bytes(&wip_proximities, &wip_token_counts)
         .expect("Could not build proximities map");
 
@@ -410,6 +599,8 @@ pub fn get_index() -> VersearchIndex {
         highlight_words,
         translation_verses_bytes,
         translation_verses_strings,
+        stop_words_bytes,
+        unstemmed_tokens_bytes,
     )
 }
 
@@ -420,58 +611,103 @@ mod tests {
     #[test]
     fn test_tokenize() {
         assert_eq!(
-            tokenize("hello, world!"),
+            tokenize("hello, world!", None),
             vec![
                 Tokenized {
                     source: "hello".to_string(),
-                    token: "HELLO".to_string()
+                    token: "HELLO".to_string(),
+                    stem: "HELLO".to_string(),
                 },
                 Tokenized {
                     source: "world".to_string(),
-                    token: "WORLD".to_string()
+                    token: "WORLD".to_string(),
+                    stem: "WORLD".to_string(),
                 }
             ]
         );
         assert_eq!(
-            tokenize("It's all good in the neighborhood which is... good"),
+            tokenize("It's all good in the neighborhood which is... good", None),
             vec![
                 Tokenized {
                     source: "It's".to_string(),
-                    token: "ITS".to_string()
+                    token: "ITS".to_string(),
+                    stem: "ITS".to_string(),
                 },
                 Tokenized {
                     source: "all".to_string(),
                     token: "ALL".to_string(),
+                    stem: "ALL".to_string(),
                 },
                 Tokenized {
                     source: "good".to_string(),
-                    token: "GOOD".to_string()
+                    token: "GOOD".to_string(),
+                    stem: "GOOD".to_string(),
                 },
                 Tokenized {
                     source: "in".to_string(),
-                    token: "IN".to_string()
+                    token: "IN".to_string(),
+                    stem: "IN".to_string(),
                 },
                 Tokenized {
                     source: "the".to_string(),
-                    token: "THE".to_string()
+                    token: "THE".to_string(),
+                    stem: "THE".to_string(),
                 },
                 Tokenized {
                     source: "neighborhood".to_string(),
-                    token: "NEIGHBORHOOD".to_string()
+                    token: "NEIGHBORHOOD".to_string(),
+                    stem: "NEIGHBORHOOD".to_string(),
                 },
                 Tokenized {
                     source: "which".to_string(),
-                    token: "WHICH".to_string()
+                    token: "WHICH".to_string(),
+                    stem: "WHICH".to_string(),
                 },
                 Tokenized {
                     source: "is".to_string(),
-                    token: "IS".to_string()
+                    token: "IS".to_string(),
+                    stem: "IS".to_string(),
                 },
                 Tokenized {
                     source: "good".to_string(),
-                    token: "GOOD".to_string()
+                    token: "GOOD".to_string(),
+                    stem: "GOOD".to_string(),
                 },
             ]
         );
     }
+
+    #[test]
+    fn test_tokenize_normalizes_diacritics() {
+        let tokens = tokenize("José habló con María", None);
+        assert_eq!(tokens[0].token, "JOSE");
+        assert_eq!(tokens[0].source, "José");
+        assert_eq!(tokens[3].token, "MARIA");
+        assert_eq!(tokens[3].source, "María");
+    }
+
+    #[test]
+    fn test_tokenize_stems_when_configured() {
+        let stemmer = resolve_stemmer(&Some("english".to_string())).unwrap();
+        let tokens = tokenize("loving loves loved", Some(&stemmer));
+        assert_eq!(tokens[0].stem, tokens[1].stem);
+        assert_eq!(tokens[1].stem, tokens[2].stem);
+        // The original surface word is preserved for highlighting
+        assert_eq!(tokens[0].source, "loving");
+    }
+
+    #[test]
+    fn test_tokenize_stems_spanish_without_crashing_on_accents() {
+        // Feeds accented input to the Spanish Snowball stemmer and checks
+        // that every stem comes back non-empty and NFKD-folded the same way
+        // `token` is, so it stays a valid, diacritic-free posting-list key.
+        let stemmer = resolve_stemmer(&Some("spanish".to_string())).unwrap();
+        let tokens = tokenize("amó con corazón", Some(&stemmer));
+        for t in &tokens {
+            assert!(t.stem.chars().all(|c| c.is_ascii_alphanumeric()));
+            assert!(!t.stem.is_empty());
+        }
+        // The stored token itself is still diacritic-folded for lookup.
+        assert_eq!(tokens[0].token, "AMO");
+    }
 }